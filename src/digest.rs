@@ -0,0 +1,152 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Self-contained CRC32 and MD5 implementations, used to fingerprint
+//! wad files and their lumps for identification and deduplication.
+
+/// Compute the CRC-32 (IEEE 802.3) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = crc32_table();
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        let index = ((crc ^ byte as u32) & 0xff) as usize;
+        crc = table[index] ^ (crc >> 8);
+    }
+    crc ^ 0xffffffff
+}
+
+/// Build the standard CRC-32 lookup table for the IEEE 802.3
+/// polynomial (reversed representation `0xedb88320`).
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for n in 0..256 {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            if c & 1 != 0 {
+                c = 0xedb88320 ^ (c >> 1);
+            } else {
+                c = c >> 1;
+            }
+        }
+        table[n] = c;
+    }
+    table
+}
+
+/// Compute the MD5 digest of `data`.
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const S: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+        5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+        4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+        6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    const K: [u32; 64] = [
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee,
+        0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+        0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be,
+        0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+        0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa,
+        0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+        0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+        0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+        0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c,
+        0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+        0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05,
+        0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+        0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039,
+        0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1,
+        0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+    ];
+
+    let mut a0 = 0x67452301u32;
+    let mut b0 = 0xefcdab89u32;
+    let mut c0 = 0x98badcfeu32;
+    let mut d0 = 0x10325476u32;
+
+    let mut message = Vec::from(data);
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    for i in 0..8 {
+        message.push((bit_len >> (8 * i)) as u8);
+    }
+
+    for chunk in message.chunks(64) {
+        let mut m = [0u32; 16];
+        for i in 0..16 {
+            m[i] = (chunk[i * 4] as u32)
+                | (chunk[i * 4 + 1] as u32) << 8
+                | (chunk[i * 4 + 2] as u32) << 16
+                | (chunk[i * 4 + 3] as u32) << 24;
+        }
+
+        let (mut a, mut b, mut c, mut d) = (a0, b0, c0, d0);
+
+        for i in 0..64 {
+            let (f, g) =
+                if i < 16 {
+                    ((b & c) | (!b & d), i)
+                } else if i < 32 {
+                    ((d & b) | (!d & c), (5 * i + 1) % 16)
+                } else if i < 48 {
+                    (b ^ c ^ d, (3 * i + 5) % 16)
+                } else {
+                    (c ^ (b | !d), (7 * i) % 16)
+                };
+            let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(S[i]));
+        }
+
+        a0 = a0.wrapping_add(a);
+        b0 = b0.wrapping_add(b);
+        c0 = c0.wrapping_add(c);
+        d0 = d0.wrapping_add(d);
+    }
+
+    let mut digest = [0u8; 16];
+    for (i, word) in [a0, b0, c0, d0].iter().enumerate() {
+        digest[i * 4] = *word as u8;
+        digest[i * 4 + 1] = (*word >> 8) as u8;
+        digest[i * 4 + 2] = (*word >> 16) as u8;
+        digest[i * 4 + 3] = (*word >> 24) as u8;
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, md5};
+
+    #[test]
+    fn crc32_of_empty() {
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc32_of_known_string() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn md5_of_empty() {
+        assert_eq!(md5(b""), [
+            0xd4, 0x1d, 0x8c, 0xd9, 0x8f, 0x00, 0xb2, 0x04,
+            0xe9, 0x80, 0x09, 0x98, 0xec, 0xf8, 0x42, 0x7e,
+        ]);
+    }
+
+    #[test]
+    fn md5_of_known_string() {
+        assert_eq!(md5(b"abc"), [
+            0x90, 0x01, 0x50, 0x98, 0x3c, 0xd2, 0x4f, 0xb0,
+            0xd6, 0x96, 0x3f, 0x7d, 0x28, 0xe1, 0x7f, 0x72,
+        ]);
+    }
+}
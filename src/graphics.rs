@@ -0,0 +1,253 @@
+// Copyright 2016 Martin Grabmueller. See the LICENSE file at the
+// top-level directory of this distribution for license information.
+
+//! Decoding of the classic Doom graphics formats (flats and
+//! pictures) into RGBA pixel buffers, using a palette loaded from a
+//! `PLAYPAL` lump.
+
+use std::io;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use mk_err;
+
+/// Width and height of a flat lump, in pixels.
+const FLAT_SIZE: usize = 64;
+
+/// An RGBA image decoded from a Doom graphics lump.
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// Width of the image, in pixels.
+    pub width: usize,
+    /// Height of the image, in pixels.
+    pub height: usize,
+    /// Horizontal offset from the image's natural drawing point, as
+    /// recorded in a picture lump's header.  Always `0` for flats.
+    pub left_offset: i32,
+    /// Vertical offset from the image's natural drawing point, as
+    /// recorded in a picture lump's header.  Always `0` for flats.
+    pub top_offset: i32,
+    /// Pixels in row-major order, each an `[r, g, b, a]` quadruplet.
+    /// Pixels not covered by any post in a picture lump are fully
+    /// transparent (`a == 0`).
+    pub pixels: Vec<[u8; 4]>,
+}
+
+/// A 256-colour RGB palette, as stored in a `PLAYPAL` lump.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    /// The 256 `[r, g, b]` palette entries, indexed by palette
+    /// index.
+    pub colors: [[u8; 3]; 256],
+}
+
+impl Palette {
+    /// Parse a `PLAYPAL` lump into a `Palette`, using its first 256
+    /// RGB triplets (a `PLAYPAL` lump may contain more than one
+    /// palette; later ones, e.g. for damage/item-pickup flashes,
+    /// are ignored).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `data` is shorter than 768 bytes.
+    pub fn from_playpal(data: &[u8]) -> io::Result<Palette> {
+        if data.len() < 768 {
+            return Err(mk_err("PLAYPAL lump is smaller than 768 bytes"));
+        }
+        let mut colors = [[0u8; 3]; 256];
+        for i in 0..256 {
+            colors[i] = [data[i * 3], data[i * 3 + 1], data[i * 3 + 2]];
+        }
+        Ok(Palette{colors: colors})
+    }
+}
+
+/// Decode a flat lump: a raw 64x64 array of palette indices.
+///
+/// # Errors
+///
+/// Returns an error if `data` is not exactly 4096 bytes.
+pub fn decode_flat(data: &[u8], palette: &Palette) -> io::Result<Image> {
+    if data.len() != FLAT_SIZE * FLAT_SIZE {
+        return Err(mk_err("flat lump is not 64x64 (4096 bytes)"));
+    }
+
+    let pixels = data.iter().map(|&index| {
+        let c = palette.colors[index as usize];
+        [c[0], c[1], c[2], 0xff]
+    }).collect();
+
+    Ok(Image{
+        width: FLAT_SIZE,
+        height: FLAT_SIZE,
+        left_offset: 0,
+        top_offset: 0,
+        pixels: pixels,
+    })
+}
+
+/// Decode a picture lump: a header giving the image dimensions and
+/// drawing offsets, followed by a column offset table and, for each
+/// column, a series of "posts" of opaque pixels separated by
+/// transparent gaps.
+///
+/// # Errors
+///
+/// Returns an error if the lump is truncated, declares a negative
+/// width or height, or contains an out-of-range column offset.
+pub fn decode_picture(data: &[u8], palette: &Palette) -> io::Result<Image> {
+    let mut cursor = io::Cursor::new(data);
+    let raw_width = try!(cursor.read_i16::<LittleEndian>());
+    let raw_height = try!(cursor.read_i16::<LittleEndian>());
+    let left_offset = try!(cursor.read_i16::<LittleEndian>()) as i32;
+    let top_offset = try!(cursor.read_i16::<LittleEndian>()) as i32;
+
+    if raw_width < 0 || raw_height < 0 {
+        return Err(mk_err("picture lump has a negative width or height"));
+    }
+    let width = raw_width as usize;
+    let height = raw_height as usize;
+
+    let mut column_offsets = Vec::with_capacity(width);
+    for _ in 0..width {
+        column_offsets.push(try!(cursor.read_i32::<LittleEndian>()));
+    }
+
+    let mut pixels = vec![[0u8, 0u8, 0u8, 0u8]; width * height];
+
+    for (x, &column_offset) in column_offsets.iter().enumerate() {
+        if column_offset < 0 || column_offset as usize >= data.len() {
+            return Err(mk_err("picture column offset out of range"));
+        }
+        try!(decode_column(data, column_offset as usize, x, width, height, palette, &mut pixels));
+    }
+
+    Ok(Image{
+        width: width,
+        height: height,
+        left_offset: left_offset,
+        top_offset: top_offset,
+        pixels: pixels,
+    })
+}
+
+/// Decode the posts of a single picture column, starting at
+/// `pos`, writing opaque pixels into `pixels` (row-major, `width`
+/// wide).
+fn decode_column(data: &[u8], mut pos: usize, x: usize, width: usize, height: usize,
+                  palette: &Palette, pixels: &mut Vec<[u8; 4]>) -> io::Result<()> {
+    loop {
+        let topdelta = try!(byte_at(data, pos));
+        pos += 1;
+        if topdelta == 0xff {
+            return Ok(());
+        }
+        let length = try!(byte_at(data, pos)) as usize;
+        pos += 2; // length byte plus one padding byte
+
+        for i in 0..length {
+            let index = try!(byte_at(data, pos + i));
+            let y = topdelta as usize + i;
+            if y < height {
+                let c = palette.colors[index as usize];
+                pixels[y * width + x] = [c[0], c[1], c[2], 0xff];
+            }
+        }
+        pos += length + 1; // post data plus one padding byte
+    }
+}
+
+/// Read a single byte from `data`, returning an error instead of
+/// panicking if `pos` is out of range.
+fn byte_at(data: &[u8], pos: usize) -> io::Result<u8> {
+    data.get(pos).cloned().ok_or_else(|| mk_err("picture lump is truncated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_flat, decode_picture, Palette, FLAT_SIZE};
+
+    fn palette_with(entries: &[(usize, [u8; 3])]) -> Palette {
+        let mut colors = [[0u8; 3]; 256];
+        for &(index, color) in entries {
+            colors[index] = color;
+        }
+        Palette{colors: colors}
+    }
+
+    // A single-column, height-5 picture with two posts separated by
+    // a transparent gap, and a trailing gap after the last post:
+    //   y=0,1: post one (palette indices 1, 2)
+    //   y=2:   transparent gap
+    //   y=3:   post two (palette index 3)
+    //   y=4:   transparent gap
+    fn one_column_picture_with_gap() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1i16.to_le_bytes()); // width
+        data.extend_from_slice(&5i16.to_le_bytes()); // height
+        data.extend_from_slice(&0i16.to_le_bytes()); // left_offset
+        data.extend_from_slice(&0i16.to_le_bytes()); // top_offset
+        data.extend_from_slice(&12i32.to_le_bytes()); // column_offsets[0]
+        // Post one: topdelta 0, length 2, data [1, 2].
+        data.extend_from_slice(&[0, 2, 0, 1, 2, 0]);
+        // Post two: topdelta 3, length 1, data [3].
+        data.extend_from_slice(&[3, 1, 0, 3, 0]);
+        data.push(0xff); // column terminator
+        data
+    }
+
+    #[test]
+    fn decode_picture_leaves_gaps_transparent() {
+        let palette = palette_with(&[
+            (1, [10, 20, 30]), (2, [40, 50, 60]), (3, [70, 80, 90]),
+        ]);
+        let image = decode_picture(&one_column_picture_with_gap(), &palette).unwrap();
+        assert_eq!(image.width, 1);
+        assert_eq!(image.height, 5);
+        assert_eq!(image.pixels[0], [10, 20, 30, 0xff]);
+        assert_eq!(image.pixels[1], [40, 50, 60, 0xff]);
+        assert_eq!(image.pixels[2], [0, 0, 0, 0]);
+        assert_eq!(image.pixels[3], [70, 80, 90, 0xff]);
+        assert_eq!(image.pixels[4], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decode_picture_rejects_negative_dimensions() {
+        let palette = palette_with(&[]);
+        let mut data = Vec::new();
+        data.extend_from_slice(&(-1i16).to_le_bytes()); // width
+        data.extend_from_slice(&1i16.to_le_bytes()); // height
+        data.extend_from_slice(&0i16.to_le_bytes()); // left_offset
+        data.extend_from_slice(&0i16.to_le_bytes()); // top_offset
+        assert!(decode_picture(&data, &palette).is_err());
+    }
+
+    #[test]
+    fn decode_picture_rejects_out_of_range_column_offset() {
+        let palette = palette_with(&[]);
+        let mut data = Vec::new();
+        data.extend_from_slice(&1i16.to_le_bytes()); // width
+        data.extend_from_slice(&1i16.to_le_bytes()); // height
+        data.extend_from_slice(&0i16.to_le_bytes()); // left_offset
+        data.extend_from_slice(&0i16.to_le_bytes()); // top_offset
+        data.extend_from_slice(&1_000_000i32.to_le_bytes()); // column_offsets[0]
+        assert!(decode_picture(&data, &palette).is_err());
+    }
+
+    #[test]
+    fn decode_flat_of_known_size() {
+        let palette = palette_with(&[(5, [1, 2, 3])]);
+        let data = [5u8; FLAT_SIZE * FLAT_SIZE];
+        let image = decode_flat(&data, &palette).unwrap();
+        assert_eq!(image.width, FLAT_SIZE);
+        assert_eq!(image.height, FLAT_SIZE);
+        assert_eq!(image.pixels.len(), FLAT_SIZE * FLAT_SIZE);
+        assert!(image.pixels.iter().all(|&p| p == [1, 2, 3, 0xff]));
+    }
+
+    #[test]
+    fn decode_flat_rejects_wrong_size() {
+        let palette = palette_with(&[]);
+        assert!(decode_flat(&[0u8; 100], &palette).is_err());
+    }
+}
@@ -2,15 +2,27 @@
 // top-level directory of this distribution for license information.
 
 extern crate byteorder;
+#[cfg(feature = "zlib")]
+extern crate flate2;
+#[cfg(feature = "lzf")]
+extern crate lzf;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::Read;
 use std::io::Seek;
+use std::io::Write;
 use std::fs::File;
 use std::io;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-/// WAD files come in two flavours: IWAD and PWAD.
+pub mod digest;
+pub mod graphics;
+pub mod resource;
+
+/// WAD files come in a few flavours, distinguished by their
+/// four-byte magic tag.
 #[derive(Debug)]
 pub enum WadType {
     /// IWADs are the main game files.  Running a game always requires
@@ -18,7 +30,14 @@ pub enum WadType {
     IWAD,
     /// PWADs, or "Patch WADs" can override most of the lumps in an
     /// IWAD.  PWADs are loaded in addition to an IWAD file.
-    PWAD
+    PWAD,
+    /// WAD2 archives are used by Quake for textures and other
+    /// game data.  They use a 32-byte directory entry layout,
+    /// distinct from the Doom IWAD/PWAD format.
+    WAD2,
+    /// WAD3 archives are used by Quake 2 and Half-Life.  They share
+    /// the WAD2 directory layout.
+    WAD3,
 }
 
 /// Individual data items are stored in lumps, which are named binary
@@ -27,8 +46,19 @@ pub enum WadType {
 pub struct Lump {
     /// Byte offset in the WAD file where the lump data starts.
     pub file_offset: i32,
-    /// Length of the lump in bytes.
-    pub size: i32
+    /// Length of the lump in bytes, after decompression.
+    pub size: i32,
+    /// Content type of the lump, as used by Quake WAD2/WAD3
+    /// archives.  Always `0` for Doom IWAD/PWAD lumps.
+    pub entry_type: u8,
+    /// Compression method used for the lump's on-disk bytes, as
+    /// used by Quake WAD2/WAD3 archives.  `0` means the lump is
+    /// stored uncompressed, which is always the case for Doom
+    /// IWAD/PWAD lumps.
+    pub compression: u8,
+    /// Size of the lump as stored on disk.  Differs from `size`
+    /// when the lump is compressed; otherwise equal to `size`.
+    pub disk_size: i32,
 }
 
 /// The `Header` structure contains information about the WAD file
@@ -46,27 +76,146 @@ pub struct Header {
     pub lumps: Vec<(String, Lump)>,
 }
 
+/// Logical groupings of lumps, delimited by marker lumps in the
+/// directory (e.g. `F_START`/`F_END` for flats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    /// Lumps outside any namespace markers.
+    Global,
+    /// Flat (floor/ceiling texture) lumps, between
+    /// `F_START`/`FF_START` and `F_END`/`FF_END`.
+    Flats,
+    /// Sprite lumps, between `S_START`/`SS_START` and
+    /// `S_END`/`SS_END`.
+    Sprites,
+    /// Patch (wall texture piece) lumps, between
+    /// `P_START`/`PP_START` and `P_END`/`PP_END`.
+    Patches,
+    /// Colormap lumps, between `C_START` and `C_END`.
+    Colormaps,
+}
+
+impl Header {
+    /// Classify each lump in the directory into the namespace it
+    /// belongs to, based on the `*_START`/`*_END` marker lumps that
+    /// precede it.  The result has one entry per lump, in directory
+    /// order.
+    ///
+    /// As a fallback for wads missing an `F_START` marker entirely,
+    /// any lump outside the sprite, patch and colormap namespaces
+    /// that lies before the wad's `F_END` (or, lacking that too,
+    /// anywhere in the file) and whose size is exactly 4096 bytes
+    /// (the size of a flat) is classified as a flat.  Wads that do have
+    /// an `F_START` marker are classified by markers alone; the
+    /// fallback never overrides them.
+    ///
+    /// Marker lumps themselves (`*_START`/`*_END`) are classified as
+    /// `Global`, not as the namespace they open or close, so that
+    /// `lumps_in_namespace` returns only actual content lumps.
+    pub fn namespaces(&self) -> Vec<Namespace> {
+        let has_f_start = self.lumps.iter()
+            .any(|&(ref name, _)| name == "F_START" || name == "FF_START");
+        let f_end_index = self.lumps.iter()
+            .position(|&(ref name, _)| name == "F_END" || name == "FF_END")
+            .unwrap_or(self.lumps.len());
+
+        let mut result = Vec::with_capacity(self.lumps.len());
+        let mut current = Namespace::Global;
+        for (index, &(ref name, ref lump)) in self.lumps.iter().enumerate() {
+            match &name[..] {
+                "F_START" | "FF_START" => {
+                    current = Namespace::Flats;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "F_END" | "FF_END" => {
+                    current = Namespace::Global;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "S_START" | "SS_START" => {
+                    current = Namespace::Sprites;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "S_END" | "SS_END" => {
+                    current = Namespace::Global;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "P_START" | "PP_START" => {
+                    current = Namespace::Patches;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "P_END" | "PP_END" => {
+                    current = Namespace::Global;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "C_START" => {
+                    current = Namespace::Colormaps;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                "C_END" => {
+                    current = Namespace::Global;
+                    result.push(Namespace::Global);
+                    continue;
+                }
+                _ => {}
+            }
+            let fallback_flat = current == Namespace::Global
+                && !has_f_start
+                && index < f_end_index
+                && lump.size == 4096;
+            let ns = if fallback_flat { Namespace::Flats } else { current };
+            result.push(ns);
+        }
+        result
+    }
+
+    /// Names and lumps that fall within the given namespace, in
+    /// directory order.
+    pub fn lumps_in_namespace(&self, ns: Namespace) -> Vec<&(String, Lump)> {
+        self.lumps.iter()
+            .zip(self.namespaces().into_iter())
+            .filter(|&(_, n)| n == ns)
+            .map(|(entry, _)| entry)
+            .collect()
+    }
+}
+
 /// Helper to create io::Error values.
-fn mk_err(msg: &str) -> io::Error {
+pub(crate) fn mk_err(msg: &str) -> io::Error {
     io::Error::new(io::ErrorKind::Other, msg)
 }
 
-/// Check the validity of lump names.
-fn validate_lump_name(name: &[u8; 8]) -> Result<(), io::Error> {
-    for c in 0..8 {
+/// Check the validity of lump names.  Accepts both the 8-byte names
+/// used by Doom IWAD/PWAD archives and the 16-byte names used by
+/// Quake WAD2/WAD3 archives.  The latter also permit lowercase
+/// letters and the `*`, `+`, `{`, `}`, `!` prefixes Quake/Half-Life
+/// use to mark animated, button, transparent and invisible
+/// textures (e.g. `+0button`, `*water1`, `{invisible`).
+fn validate_lump_name(name: &[u8]) -> Result<(), io::Error> {
+    let len = name.len();
+    let extended = len > 8;
+    for c in 0..len {
         match name[c] {
             b'A'...b'Z' => {},
+            b'a'...b'z' if extended => {},
             b'0'...b'9' => {},
             b'[' => {},
             b']' => {},
             b'-' => {},
             b'_' => {},
             b'\\' => {},
+            b'*' | b'+' | b'{' | b'}' | b'!' if extended => {},
             0 => {
                 if c == 0 {
                     return Err(mk_err(&format!("{:?}: empty lump name", String::from_utf8_lossy(name))))
                 }
-                for i in c..8 {
+                for i in c..len {
                     if name[i] != 0 {
                         return Err(mk_err(&format!("{:?}: non-0 after 0 character in lump name", String::from_utf8_lossy(name))))
                     }
@@ -80,6 +229,16 @@ fn validate_lump_name(name: &[u8; 8]) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// Trim the trailing NUL padding off a raw, fixed-width lump name
+/// and decode it as a string.
+pub(crate) fn lump_name_to_string(name: &[u8]) -> String {
+    let mut d = name.len();
+    while d > 0 && name[d - 1] == 0 {
+        d -= 1;
+    }
+    String::from(String::from_utf8_lossy(&name[..d]))
+}
+
 /// Read header and directory information from the given WAD file.
 ///
 /// # Errors
@@ -102,6 +261,10 @@ pub fn read_header(wad_filename: &str) -> Result<Header, io::Error> {
             WadType::IWAD
         } else if &magic[..] == b"PWAD" {
             WadType::PWAD
+        } else if &magic[..] == b"WAD2" {
+            WadType::WAD2
+        } else if &magic[..] == b"WAD3" {
+            WadType::WAD3
         } else {
             return Err(mk_err("invalid WAD tag"));
         };
@@ -121,35 +284,79 @@ pub fn read_header(wad_filename: &str) -> Result<Header, io::Error> {
         return Err(mk_err("cannot seek to directory start"));
     }
 
-    let mut lump_name = [0u8; 8];
     let mut lump_names = Vec::new();
 
-    for _ in 0..directory_entry_count {
-        let lump_ptr = try!(f.read_i32::<LittleEndian>());
-        if lump_ptr < 0 {
-            return Err(mk_err("lump start pointer is negative"));
-        } else if lump_ptr as u64 > file_size {
-            return Err(mk_err("lump start pointer is too large"));
-        }
-        let lump_size = try!(f.read_i32::<LittleEndian>());
-        if lump_size < 0 {
-            return Err(mk_err("lump size is negative"));
-        } else if lump_ptr as u64 + lump_size as u64 > file_size {
-            return Err(mk_err("lump size is too large"));
+    match wad_type {
+        WadType::IWAD | WadType::PWAD => {
+            let mut lump_name = [0u8; 8];
+            for _ in 0..directory_entry_count {
+                let lump_ptr = try!(f.read_i32::<LittleEndian>());
+                if lump_ptr < 0 {
+                    return Err(mk_err("lump start pointer is negative"));
+                } else if lump_ptr as u64 > file_size {
+                    return Err(mk_err("lump start pointer is too large"));
+                }
+                let lump_size = try!(f.read_i32::<LittleEndian>());
+                if lump_size < 0 {
+                    return Err(mk_err("lump size is negative"));
+                } else if lump_ptr as u64 + lump_size as u64 > file_size {
+                    return Err(mk_err("lump size is too large"));
+                }
+                try!(f.read_exact(&mut lump_name));
+
+                try!(validate_lump_name(&lump_name));
+
+                let name = lump_name_to_string(&lump_name);
+                let entry = (name, Lump{
+                    file_offset: lump_ptr,
+                    size: lump_size,
+                    entry_type: 0,
+                    compression: 0,
+                    disk_size: lump_size,
+                });
+                lump_names.push(entry);
+            }
         }
-        try!(f.read_exact(&mut lump_name));
+        WadType::WAD2 | WadType::WAD3 => {
+            let mut lump_name = [0u8; 16];
+            for _ in 0..directory_entry_count {
+                let lump_ptr = try!(f.read_i32::<LittleEndian>());
+                if lump_ptr < 0 {
+                    return Err(mk_err("lump start pointer is negative"));
+                } else if lump_ptr as u64 > file_size {
+                    return Err(mk_err("lump start pointer is too large"));
+                }
+                let disk_size = try!(f.read_i32::<LittleEndian>());
+                if disk_size < 0 {
+                    return Err(mk_err("lump disk size is negative"));
+                } else if lump_ptr as u64 + disk_size as u64 > file_size {
+                    return Err(mk_err("lump disk size is too large"));
+                }
+                let lump_size = try!(f.read_i32::<LittleEndian>());
+                if lump_size < 0 {
+                    return Err(mk_err("lump size is negative"));
+                }
+                let entry_type = try!(f.read_u8());
+                let compression = try!(f.read_u8());
+                let mut padding = [0u8; 2];
+                try!(f.read_exact(&mut padding));
+                try!(f.read_exact(&mut lump_name));
 
-        try!(validate_lump_name(&lump_name));
-        
-        let mut d = 8;
-        while d > 0 && lump_name[d - 1] == 0 {
-            d -= 1;
+                try!(validate_lump_name(&lump_name));
+
+                let name = lump_name_to_string(&lump_name);
+                let entry = (name, Lump{
+                    file_offset: lump_ptr,
+                    size: lump_size,
+                    entry_type: entry_type,
+                    compression: compression,
+                    disk_size: disk_size,
+                });
+                lump_names.push(entry);
+            }
         }
-        let name = String::from(String::from_utf8_lossy(&lump_name[..d]));
-        let entry = (name, Lump{file_offset: lump_ptr, size: lump_size});
-        lump_names.push(entry);
     }
-    
+
     let hdr = Header{
         wad_type: wad_type,
         directory_entry_count: directory_entry_count,
@@ -159,10 +366,357 @@ pub fn read_header(wad_filename: &str) -> Result<Header, io::Error> {
     Ok(hdr)
 }
 
+/// A `WadFile` combines the parsed `Header` and directory with an
+/// open handle onto the underlying file, so that lump contents can
+/// be read on demand.
+pub struct WadFile {
+    file: RefCell<File>,
+    /// Header and directory of this WAD file.
+    pub header: Header,
+}
+
+impl WadFile {
+    /// Open a WAD file, reading its header and directory.
+    ///
+    /// # Errors
+    ///
+    /// See `read_header`.
+    pub fn open(wad_filename: &str) -> Result<WadFile, io::Error> {
+        let header = try!(read_header(wad_filename));
+        let file = try!(File::open(wad_filename));
+        Ok(WadFile{file: RefCell::new(file), header: header})
+    }
+
+    /// Read the raw, on-disk bytes of the lump at the given
+    /// directory index.  For compressed lumps (see
+    /// `read_lump_decompressed`), these are the compressed bytes,
+    /// not the original lump contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `index` is out of range or if the
+    /// underlying file cannot be read.
+    pub fn read_lump(&self, index: usize) -> io::Result<Vec<u8>> {
+        let &(_, ref lump) =
+            try!(self.header.lumps.get(index)
+                 .ok_or_else(|| mk_err("lump index out of range")));
+
+        let mut buf = vec![0u8; lump.disk_size as usize];
+        let mut file = self.file.borrow_mut();
+        try!(file.seek(io::SeekFrom::Start(lump.file_offset as u64)));
+        try!(file.read_exact(&mut buf));
+        Ok(buf)
+    }
+
+    /// Read the raw bytes of the lump with the given name, if one
+    /// is present in the directory.  If there are several lumps
+    /// with the same name, the first one is returned.  Note that
+    /// this differs from how Doom-family engines themselves resolve
+    /// a name: they use the *last* matching lump, so that a PWAD's
+    /// lumps override an IWAD's earlier ones of the same name (see
+    /// `crc_map`, which follows that last-match convention).
+    pub fn read_lump_by_name(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.header.lumps.iter().position(|&(ref n, _)| n == name) {
+            Some(index) => self.read_lump(index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the lump at the given directory index, transparently
+    /// decompressing it according to `Lump::compression` if
+    /// necessary.  Uncompressed lumps are returned unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `index` is out of range, if the
+    /// compression method is unknown or unsupported (the relevant
+    /// `zlib`/`lzf` cargo feature must be enabled), or if the
+    /// decompressed data does not match the declared `Lump::size`.
+    pub fn read_lump_decompressed(&self, index: usize) -> io::Result<Vec<u8>> {
+        let &(_, ref lump) =
+            try!(self.header.lumps.get(index)
+                 .ok_or_else(|| mk_err("lump index out of range")));
+        let size = lump.size as usize;
+        let compression = try!(compression_of(lump.compression));
+
+        let raw = try!(self.read_lump(index));
+
+        let decompressed = match compression {
+            Compression::None => raw,
+            Compression::Zlib => try!(inflate_zlib(&raw, size)),
+            Compression::Lzf => try!(inflate_lzf(&raw, size)),
+        };
+
+        if decompressed.len() != size {
+            return Err(mk_err("decompressed lump size does not match directory entry"));
+        }
+        Ok(decompressed)
+    }
+
+    /// Compute the CRC32 checksum of the lump at the given
+    /// directory index.  Hashing is lazy: the lump's bytes are only
+    /// read from disk when this method is called.
+    pub fn lump_crc(&self, index: usize) -> io::Result<u32> {
+        let data = try!(self.read_lump(index));
+        Ok(digest::crc32(&data))
+    }
+
+    /// Compute the MD5 digest of the whole underlying WAD file.
+    pub fn file_md5(&self) -> io::Result<[u8; 16]> {
+        let mut data = Vec::new();
+        let mut file = self.file.borrow_mut();
+        try!(file.seek(io::SeekFrom::Start(0)));
+        try!(file.read_to_end(&mut data));
+        Ok(digest::md5(&data))
+    }
+
+    /// Build a map from lump name to the CRC32 checksum of its
+    /// contents, covering the whole directory.  Lumps sharing a
+    /// name overwrite each other's entry, keeping the last one in
+    /// directory order.
+    pub fn crc_map(&self) -> io::Result<HashMap<String, u32>> {
+        let mut map = HashMap::with_capacity(self.header.lumps.len());
+        for index in 0..self.header.lumps.len() {
+            let name = self.header.lumps[index].0.clone();
+            let crc = try!(self.lump_crc(index));
+            map.insert(name, crc);
+        }
+        Ok(map)
+    }
+}
+
+impl resource::ResourceFile for WadFile {
+    fn lump_count(&self) -> usize {
+        self.header.lumps.len()
+    }
+
+    fn lump_name(&self, index: usize) -> Option<&str> {
+        self.header.lumps.get(index).map(|&(ref name, _)| &name[..])
+    }
+
+    fn read_lump(&self, index: usize) -> io::Result<Vec<u8>> {
+        WadFile::read_lump(self, index)
+    }
+}
+
+/// Compression methods used for WAD2/WAD3 lump data, as recorded in
+/// `Lump::compression`.
+#[derive(Debug, PartialEq, Eq)]
+enum Compression {
+    /// Lump data is stored as-is.
+    None,
+    /// Lump data is zlib-deflated.
+    Zlib,
+    /// Lump data is LZF-compressed.
+    Lzf,
+}
+
+/// Map a raw `Lump::compression` byte to a `Compression` value.
+fn compression_of(byte: u8) -> Result<Compression, io::Error> {
+    match byte {
+        0 => Ok(Compression::None),
+        1 => Ok(Compression::Zlib),
+        2 => Ok(Compression::Lzf),
+        b => Err(mk_err(&format!("unknown lump compression method: {}", b))),
+    }
+}
+
+#[cfg(feature = "zlib")]
+fn inflate_zlib(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    let mut decoder = flate2::read::ZlibDecoder::new(data);
+    let mut out = Vec::with_capacity(size);
+    try!(decoder.read_to_end(&mut out));
+    Ok(out)
+}
+
+#[cfg(not(feature = "zlib"))]
+fn inflate_zlib(_data: &[u8], _size: usize) -> io::Result<Vec<u8>> {
+    Err(mk_err("zlib decompression requires the \"zlib\" cargo feature"))
+}
+
+#[cfg(feature = "lzf")]
+fn inflate_lzf(data: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    lzf::decompress(data, size).map_err(|_| mk_err("LZF decompression failed"))
+}
+
+#[cfg(not(feature = "lzf"))]
+fn inflate_lzf(_data: &[u8], _size: usize) -> io::Result<Vec<u8>> {
+    Err(mk_err("LZF decompression requires the \"lzf\" cargo feature"))
+}
+
+/// Accumulates named lumps and writes them out as a valid Doom
+/// IWAD or PWAD file, the write-side counterpart to `read_header`
+/// and `WadFile`.
+pub struct WadBuilder {
+    wad_type: WadType,
+    lumps: Vec<(String, Vec<u8>)>,
+}
+
+impl WadBuilder {
+    /// Start building a new, empty WAD of the given type.
+    pub fn new(wad_type: WadType) -> WadBuilder {
+        WadBuilder{wad_type: wad_type, lumps: Vec::new()}
+    }
+
+    /// Append a lump with the given name and contents.  The name is
+    /// validated immediately, so that a bad name is reported close
+    /// to its cause rather than when `write` is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is longer than 8 bytes or
+    /// contains characters `validate_lump_name` rejects.
+    pub fn add_lump(&mut self, name: &str, data: Vec<u8>) -> io::Result<()> {
+        let bytes = name.as_bytes();
+        if bytes.len() > 8 {
+            return Err(mk_err(&format!("{:?}: lump name is longer than 8 bytes", name)));
+        }
+        let mut lump_name = [0u8; 8];
+        lump_name[..bytes.len()].copy_from_slice(bytes);
+        try!(validate_lump_name(&lump_name));
+
+        self.lumps.push((String::from(name), data));
+        Ok(())
+    }
+
+    /// Write the accumulated lumps to `wad_filename` as a WAD file:
+    /// the 12-byte header, the lump data in insertion order, then
+    /// the 16-byte-per-entry directory, backpatching
+    /// `directory_start` in the header once the directory position
+    /// is known.
+    ///
+    /// # Errors
+    ///
+    /// IO errors are directly returned.  Writing a `WAD2`/`WAD3`
+    /// archive is not supported, since their directory layout is
+    /// not implemented here.
+    pub fn write(&self, wad_filename: &str) -> io::Result<()> {
+        let tag: &[u8; 4] = match self.wad_type {
+            WadType::IWAD => b"IWAD",
+            WadType::PWAD => b"PWAD",
+            WadType::WAD2 | WadType::WAD3 =>
+                return Err(mk_err("writing WAD2/WAD3 archives is not supported")),
+        };
+
+        let mut f = try!(File::create(wad_filename));
+        try!(f.write_all(tag));
+        try!(f.write_i32::<LittleEndian>(self.lumps.len() as i32));
+        // Placeholder for directory_start; backpatched below once known.
+        try!(f.write_i32::<LittleEndian>(0));
+
+        let mut offsets = Vec::with_capacity(self.lumps.len());
+        let mut offset = 12i32;
+        for &(_, ref data) in &self.lumps {
+            try!(f.write_all(data));
+            offsets.push(offset);
+            offset += data.len() as i32;
+        }
+        let directory_start = offset;
+
+        for (&(ref name, ref data), &lump_offset) in self.lumps.iter().zip(offsets.iter()) {
+            let bytes = name.as_bytes();
+            let mut lump_name = [0u8; 8];
+            lump_name[..bytes.len()].copy_from_slice(bytes);
+
+            try!(f.write_i32::<LittleEndian>(lump_offset));
+            try!(f.write_i32::<LittleEndian>(data.len() as i32));
+            try!(f.write_all(&lump_name));
+        }
+
+        try!(f.seek(io::SeekFrom::Start(8)));
+        try!(f.write_i32::<LittleEndian>(directory_start));
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::validate_lump_name;
-    
+    use std::fs;
+
+    use super::{validate_lump_name, Header, Lump, Namespace, WadBuilder, WadFile, WadType};
+
+    /// A path under the OS temp directory, unique to this test
+    /// process, so that tests writing WAD files don't collide with
+    /// each other or with a previous run.
+    fn temp_wad_path(tag: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("wadfile_test_{}_{}.wad", tag, std::process::id()))
+    }
+
+    fn lump(size: i32) -> Lump {
+        Lump{file_offset: 0, size: size, entry_type: 0, compression: 0, disk_size: size}
+    }
+
+    fn header(names: &[(&str, i32)]) -> Header {
+        let lumps = names.iter()
+            .map(|&(name, size)| (String::from(name), lump(size)))
+            .collect();
+        Header{wad_type: WadType::IWAD, directory_entry_count: names.len() as i32,
+               directory_start: 0, lumps: lumps}
+    }
+
+    #[test]
+    fn namespaces_marker_delimited() {
+        let hdr = header(&[
+            ("F_START", 0), ("FLAT1", 4096), ("F_END", 0),
+            ("S_START", 0), ("TROOA1", 100), ("S_END", 0),
+        ]);
+        assert_eq!(hdr.namespaces(), vec![
+            Namespace::Global, Namespace::Flats, Namespace::Global,
+            Namespace::Global, Namespace::Sprites, Namespace::Global,
+        ]);
+    }
+
+    #[test]
+    fn namespaces_markers_are_global() {
+        let hdr = header(&[
+            ("P_START", 0), ("WALL1", 100), ("P_END", 0),
+            ("C_START", 0), ("COLORMAP", 256), ("C_END", 0),
+        ]);
+        let ns = hdr.namespaces();
+        assert_eq!(ns[0], Namespace::Global);
+        assert_eq!(ns[2], Namespace::Global);
+        assert_eq!(ns[3], Namespace::Global);
+        assert_eq!(ns[5], Namespace::Global);
+    }
+
+    #[test]
+    fn namespaces_fallback_scoped_to_missing_f_start() {
+        // No F_START anywhere: a 4096-byte lump before F_END falls
+        // back to Flats, but one after F_END does not.
+        let hdr = header(&[
+            ("BIGLUMP", 4096), ("F_END", 0), ("BIGLUMP2", 4096),
+        ]);
+        assert_eq!(hdr.namespaces(), vec![
+            Namespace::Flats, Namespace::Global, Namespace::Global,
+        ]);
+    }
+
+    #[test]
+    fn namespaces_fallback_does_not_apply_with_f_start() {
+        // A wad with a proper F_START/F_END must not have ordinary
+        // 4096-byte global lumps misclassified as flats.
+        let hdr = header(&[
+            ("BIGLUMP", 4096), ("F_START", 0), ("FLAT1", 4096), ("F_END", 0),
+        ]);
+        assert_eq!(hdr.namespaces(), vec![
+            Namespace::Global, Namespace::Global, Namespace::Flats, Namespace::Global,
+        ]);
+    }
+
+    #[test]
+    fn namespaces_fallback_does_not_override_other_namespaces() {
+        // No F_START in the wad at all, but a 4096-byte lump inside
+        // an S_START/S_END (or P_START/P_END) block must keep its
+        // enclosing namespace, not be stolen by the flat fallback.
+        let hdr = header(&[
+            ("S_START", 0), ("BIGSPR1", 4096), ("S_END", 0),
+        ]);
+        assert_eq!(hdr.namespaces(), vec![
+            Namespace::Global, Namespace::Sprites, Namespace::Global,
+        ]);
+    }
+
     #[test]
     fn validate_lump_name0() {
         assert!(validate_lump_name(b"MAP32\0\0\0").is_ok());
@@ -188,4 +742,79 @@ mod tests {
         // This appears in Memento Mori's MM.WAD.
         assert!(validate_lump_name(b"DEMO3\0\0S").is_err());
     }
+    #[test]
+    fn validate_lump_name6() {
+        // Quake WAD2/WAD3 lump names are lowercase and up to 16 bytes.
+        assert!(validate_lump_name(b"city4_5\0\0\0\0\0\0\0\0\0").is_ok());
+    }
+    #[test]
+    fn validate_lump_name7() {
+        // Quake/Half-Life animated, button and transparent textures
+        // use a punctuation prefix, only allowed in 16-byte names.
+        assert!(validate_lump_name(b"+0button\0\0\0\0\0\0\0\0").is_ok());
+        assert!(validate_lump_name(b"*water1\0\0\0\0\0\0\0\0\0").is_ok());
+        assert!(validate_lump_name(b"+0\0\0\0\0\0\0").is_err());
+    }
+
+    #[test]
+    fn wadbuilder_round_trip() {
+        let path = temp_wad_path("round_trip");
+        let path_str = path.to_str().unwrap();
+
+        let mut builder = WadBuilder::new(WadType::PWAD);
+        builder.add_lump("LUMPA", vec![1, 2, 3, 4]).unwrap();
+        builder.add_lump("LUMPB", vec![]).unwrap();
+        builder.write(path_str).unwrap();
+
+        let wad = WadFile::open(path_str).unwrap();
+        assert_eq!(wad.header.lumps.len(), 2);
+        assert_eq!(wad.header.lumps[0].0, "LUMPA");
+        assert_eq!(wad.header.lumps[0].1.size, 4);
+        assert_eq!(wad.header.lumps[1].0, "LUMPB");
+        assert_eq!(wad.header.lumps[1].1.size, 0);
+        assert_eq!(wad.read_lump(0).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(wad.read_lump(1).unwrap(), Vec::<u8>::new());
+        assert_eq!(wad.read_lump_by_name("LUMPA").unwrap(), Some(vec![1, 2, 3, 4]));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wadbuilder_empty_wad_round_trip() {
+        let path = temp_wad_path("empty");
+        let path_str = path.to_str().unwrap();
+
+        let builder = WadBuilder::new(WadType::PWAD);
+        builder.write(path_str).unwrap();
+
+        let wad = WadFile::open(path_str).unwrap();
+        assert_eq!(wad.header.lumps.len(), 0);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wadbuilder_rejects_long_name() {
+        let mut builder = WadBuilder::new(WadType::PWAD);
+        assert!(builder.add_lump("TOOLONGNAME", vec![]).is_err());
+    }
+
+    #[test]
+    fn read_lump_reads_by_index_and_name() {
+        let path = temp_wad_path("read_lump");
+        let path_str = path.to_str().unwrap();
+
+        let mut builder = WadBuilder::new(WadType::PWAD);
+        builder.add_lump("FIRST", vec![9, 8, 7]).unwrap();
+        builder.add_lump("SECOND", vec![1, 2, 3, 4, 5]).unwrap();
+        builder.write(path_str).unwrap();
+
+        let wad = WadFile::open(path_str).unwrap();
+        assert_eq!(wad.read_lump(1).unwrap(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(wad.read_lump_by_name("FIRST").unwrap(), Some(vec![9, 8, 7]));
+        assert_eq!(wad.read_lump_by_name("MISSING").unwrap(), None);
+        assert!(wad.read_lump(2).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
 }